@@ -9,10 +9,61 @@ use std::{env, fs, io};
 
 use cmake::Config as CmakeConfig;
 use pkg_config::{Config as PkgConfig, Library};
+#[cfg(feature = "vcpkg")]
+use vcpkg::Config as VcpkgConfig;
 use walkdir::WalkDir;
 
 const GRPC_VERSION: &str = "1.35.0";
 
+// Precomputed closure of the static archives produced by the vendored
+// gRPC/abseil-cpp submodules at GRPC_VERSION. `libs/opt/pkgconfig/*.pc`
+// isn't emitted by every CMake generator and, per grpc/grpc#24512, is
+// missing entries even when it is, so these arrays are authoritative and
+// the `.pc` scraping below only supplements them.
+const COMMON_DEPS: &[&str] = &[
+    "absl_bad_optional_access",
+    "absl_base",
+    "absl_city",
+    "absl_cord",
+    "absl_debugging_internal",
+    "absl_demangle_internal",
+    "absl_examine_stack",
+    "absl_exponential_biased",
+    "absl_graphcycles_internal",
+    "absl_hash",
+    "absl_int128",
+    "absl_malloc_internal",
+    "absl_periodic_sampler",
+    "absl_raw_hash_set",
+    "absl_raw_logging_internal",
+    "absl_spinlock_wait",
+    "absl_stacktrace",
+    "absl_status",
+    "absl_statusor",
+    "absl_str_format_internal",
+    "absl_strings",
+    "absl_strings_internal",
+    "absl_symbolize",
+    "absl_synchronization",
+    "absl_throw_delegate",
+    "absl_time",
+    "absl_time_zone",
+    "address_sorting",
+    "cares",
+    "re2",
+    "upb",
+    "z",
+];
+
+// Core archives produced by the gRPC/gpr CMake targets themselves, as
+// opposed to their third_party dependency closure in `COMMON_DEPS`. `.pc`
+// scraping is unavailable on exactly the platforms (Windows, musl) this
+// needs to be authoritative on, so these are linked whether or not the
+// `.pc` files exist (`try_vcpkg` links them only on a crt-static target); the
+// build's own `library` target (`grpc`/`grpc_unsecure`) is added to this
+// set alongside `gpr` wherever it's used.
+const GRPC_DEPS: &[&str] = &["gpr"];
+
 fn probe_library(library: &str, cargo_metadata: bool) -> Library {
     match PkgConfig::new()
         .atleast_version(GRPC_VERSION)
@@ -24,6 +75,63 @@ fn probe_library(library: &str, cargo_metadata: bool) -> Library {
     }
 }
 
+// Probe for a system-wide gRPC when the `static-grpc` feature is disabled,
+// following the pattern curl-sys uses for its own `static-curl` flag. On
+// success, `build_grpc` is skipped entirely and `main` only needs to
+// compile grpc_wrap.cc against the paths gathered here.
+fn try_system_lib(cc: &mut cc::Build, library: &str) -> bool {
+    let target_os = get_env("CARGO_CFG_TARGET_OS");
+    let is_macos = target_os.as_deref() == Some("macos");
+    let is_apple = is_macos || target_os.as_deref() == Some("ios");
+
+    if cfg!(feature = "force-system-lib-on-osx") && is_macos {
+        println!("cargo:rustc-link-lib={}", library);
+        // No pkg-config probe ran to tell `cc` where the headers are;
+        // cover the common Homebrew install prefixes on Intel and Apple
+        // Silicon.
+        add_homebrew_includes(cc);
+        return true;
+    }
+
+    match PkgConfig::new()
+        .atleast_version(GRPC_VERSION)
+        .cargo_metadata(true)
+        .probe(library)
+    {
+        Ok(lib) => {
+            for inc_path in lib.include_paths {
+                cc.include(inc_path);
+            }
+            true
+        }
+        Err(e) => {
+            // pkg-config .pc files for gRPC are routinely missing on Apple
+            // platforms even when the library itself is installed (e.g.
+            // via Homebrew); fall back to a plain `-l` there.
+            if is_apple {
+                println!(
+                    "cargo:warning=pkg-config couldn't find {}: {:?}; linking -l{} directly",
+                    library, e, library
+                );
+                println!("cargo:rustc-link-lib={}", library);
+                // As above, nothing probed the headers' location for us.
+                add_homebrew_includes(cc);
+                true
+            } else {
+                false
+            }
+        }
+    }
+}
+
+// Cover the common Homebrew install prefixes on Intel and Apple Silicon for
+// the Apple fallbacks above, which skip (or fail) the pkg-config probe that
+// would otherwise tell `cc` where the headers are.
+fn add_homebrew_includes(cc: &mut cc::Build) {
+    cc.include("/usr/local/include");
+    cc.include("/opt/homebrew/include");
+}
+
 fn prepare_grpc() {
     let modules = vec![
         "grpc",
@@ -49,6 +157,69 @@ fn is_directory_empty<P: AsRef<Path>>(p: P) -> Result<bool, io::Error> {
     Ok(entries.next().is_none())
 }
 
+// Try to find a prebuilt gRPC via vcpkg. This is mainly useful on MSVC,
+// where pkg-config is essentially unavailable and building gRPC from
+// source is slow. Returns true (and configures `cc` with the discovered
+// include paths) when a usable package was found, in which case the
+// caller should skip `build_grpc` entirely.
+#[cfg(feature = "vcpkg")]
+fn try_vcpkg(cc: &mut cc::Build, library: &str) -> bool {
+    // Check the target, not the host, the same way the macOS checks in
+    // try_system_lib do: cfg!(target_env = "msvc") would key off the host triple and stay
+    // false forever when cross-compiling to MSVC.
+    let is_msvc = get_env("CARGO_CFG_TARGET_ENV").map_or(false, |s| s == "msvc");
+    if !is_msvc {
+        return false;
+    }
+
+    // vcpkg links differently depending on whether the crt is statically
+    // linked; mirror whatever target feature cargo resolved for us.
+    let target_feature_is_static_crt = get_env("CARGO_CFG_TARGET_FEATURE")
+        .map_or(false, |features| {
+            features.split(',').any(|f| f == "crt-static")
+        });
+
+    let mut cfg = VcpkgConfig::new();
+    cfg.copy_dlls(!target_feature_is_static_crt);
+
+    let lib = match cfg.find_package("grpc") {
+        Ok(lib) => lib,
+        Err(e) => {
+            println!("cargo:warning=vcpkg could not find grpc: {}", e);
+            return false;
+        }
+    };
+
+    // `find_package` emitted rustc-link-search/rustc-link-lib metadata for
+    // the libraries it found (vcpkg::Config::cargo_metadata defaults to
+    // true). On a static triplet (crt-static) vcpkg doesn't always surface
+    // every static archive in the transitive abseil/upb/cares/re2 closure
+    // or the core gpr/grpc archives, so link the same COMMON_DEPS/GRPC_DEPS
+    // arrays build_grpc relies on; on a dynamic triplet those are already
+    // folded into the shared grpc.dll import library, so linking them again
+    // as static archives would conflict.
+    if target_feature_is_static_crt {
+        for dep in COMMON_DEPS
+            .iter()
+            .chain(GRPC_DEPS.iter())
+            .chain(std::iter::once(&library))
+        {
+            println!("cargo:rustc-link-lib=static={}", dep);
+        }
+    }
+
+    for path in &lib.include_paths {
+        cc.include(path);
+    }
+
+    true
+}
+
+#[cfg(not(feature = "vcpkg"))]
+fn try_vcpkg(_cc: &mut cc::Build, _library: &str) -> bool {
+    false
+}
+
 fn trim_start<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
     if s.starts_with(prefix) {
         Some(s.trim_start_matches(prefix))
@@ -83,6 +254,77 @@ fn clean_up_stale_cache(cxx_compiler: String) {
     }
 }
 
+// Map `CARGO_CFG_TARGET_ARCH` to the NDK's per-ABI `ANDROID_ABI` name.
+const ANDROID_ABI: &[(&str, &str)] = &[
+    ("aarch64", "arm64-v8a"),
+    ("arm", "armeabi-v7a"),
+    ("x86", "x86"),
+    ("x86_64", "x86_64"),
+];
+
+// Legacy (NDK < 19) per-arch GCC toolchain name, used instead of the
+// unified Clang toolchain when the `ndk-old-gcc` feature is enabled.
+const ANDROID_LEGACY_TOOLCHAIN: &[(&str, &str)] = &[
+    ("aarch64", "aarch64-linux-android-4.9"),
+    ("arm", "arm-linux-androideabi-4.9"),
+    ("x86", "x86-4.9"),
+    ("x86_64", "x86_64-4.9"),
+];
+
+fn android_abi(arch: &str) -> &'static str {
+    ANDROID_ABI
+        .iter()
+        .find(|(a, _)| *a == arch)
+        .unwrap_or_else(|| panic!("unsupported Android CARGO_CFG_TARGET_ARCH {}", arch))
+        .1
+}
+
+// NDK toolchain settings resolved once per build, so that both the gRPC
+// CMake invocation and the BoringSSL sub-build in `build_boringssl` can be
+// pointed at the identical ABI/API level.
+struct AndroidConfig {
+    abi: &'static str,
+    api_level: String,
+    toolchain_file: String,
+}
+
+// Wire up the NDK toolchain for cross-compiling to Android, replacing the
+// previous ad-hoc `CMAKE_TARGET_OVERRIDE` workaround.
+fn configure_android(config: &mut CmakeConfig) -> AndroidConfig {
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let ndk_home = get_env("ANDROID_NDK_HOME")
+        .expect("ANDROID_NDK_HOME must be set when building for Android");
+    let api_level = get_env("ANDROID_API_LEVEL").unwrap_or_else(|| "21".to_owned());
+    let toolchain_file = format!("{}/build/cmake/android.toolchain.cmake", ndk_home);
+    let abi = android_abi(&arch);
+
+    config
+        .define("CMAKE_TOOLCHAIN_FILE", &toolchain_file)
+        .define("ANDROID_ABI", abi)
+        .define("ANDROID_PLATFORM", format!("android-{}", api_level))
+        .define("CMAKE_ANDROID_API", &api_level);
+
+    if cfg!(feature = "ndk-old-gcc") {
+        let toolchain_name = ANDROID_LEGACY_TOOLCHAIN
+            .iter()
+            .find(|(a, _)| *a == arch)
+            .unwrap_or_else(|| {
+                panic!(
+                    "no legacy NDK toolchain known for CARGO_CFG_TARGET_ARCH {}",
+                    arch
+                )
+            })
+            .1;
+        config.define("ANDROID_TOOLCHAIN_NAME", toolchain_name);
+    }
+
+    AndroidConfig {
+        abi,
+        api_level,
+        toolchain_file,
+    }
+}
+
 fn build_grpc(cc: &mut cc::Build, library: &str) {
     prepare_grpc();
 
@@ -142,8 +384,15 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
             _ => {}
         };
 
-        // Allow overriding of the target passed to cmake
-        // (needed for Android crosscompile)
+        // Cross-compile support for Android, wiring up the NDK's own
+        // CMake toolchain file instead of relying on CMAKE_TARGET_OVERRIDE.
+        let android = if get_env("CARGO_CFG_TARGET_OS").map_or(false, |s| s == "android") {
+            Some(configure_android(&mut config))
+        } else {
+            None
+        };
+
+        // Allow overriding of the target passed to cmake.
         if let Ok(val) = env::var("CMAKE_TARGET_OVERRIDE") {
             config.target(&val);
         }
@@ -157,13 +406,7 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
         // We don't need to build benchmarks.
         config.define("gRPC_BENCHMARK_PROVIDER", "none");
         config.define("gRPC_SSL_PROVIDER", "package");
-        if cfg!(feature = "openssl") {
-            if cfg!(feature = "openssl-vendored") {
-                config.register_dep("openssl");
-            }
-        } else {
-            build_boringssl(&mut config);
-        }
+        configure_ssl_provider(&mut config, android.as_ref());
         if cfg!(feature = "no-omit-frame-pointer") {
             config
                 .cflag("-fno-omit-frame-pointer")
@@ -190,8 +433,15 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
         }
     }
 
+    // The .pc files aren't emitted (or accurate) on every platform/generator,
+    // e.g. Windows and musl, so fold in whatever they do report as a
+    // supplement to the authoritative COMMON_DEPS/GRPC_DEPS arrays rather
+    // than relying on them for the core libraries.
     let collect = |path, to: &mut HashSet<_>| {
-        let f = fs::File::open(format!("{}/libs/opt/pkgconfig/{}.pc", build_dir, path)).unwrap();
+        let f = match fs::File::open(format!("{}/libs/opt/pkgconfig/{}.pc", build_dir, path)) {
+            Ok(f) => f,
+            Err(_) => return,
+        };
         for l in io::BufReader::new(f).lines() {
             let l = l.unwrap();
             if l.starts_with("Libs: ") {
@@ -203,7 +453,12 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
             }
         }
     };
-    let mut libs = HashSet::new();
+    let mut libs: HashSet<String> = COMMON_DEPS
+        .iter()
+        .chain(GRPC_DEPS.iter())
+        .map(|s| s.to_string())
+        .collect();
+    libs.insert(library.to_string());
     collect("gpr", &mut libs);
     collect(library, &mut libs);
     for l in libs {
@@ -217,12 +472,6 @@ fn build_grpc(cc: &mut cc::Build, library: &str) {
             println!("cargo:rustc-link-lib=static=ssl");
             println!("cargo:rustc-link-lib=static=crypto");
         }
-    } else {
-        // grpc_unsecure.pc is not accurate, see also grpc/grpc#24512.
-        println!("cargo:rustc-link-lib=static=upb");
-        println!("cargo:rustc-link-lib=static=cares");
-        println!("cargo:rustc-link-lib=static=z");
-        println!("cargo:rustc-link-lib=static=address_sorting");
     }
 
     cc.include("grpc/include");
@@ -255,17 +504,95 @@ fn figure_ssl_path(build_dir: &str) {
     println!("cargo:rustc-link-lib=crypto");
 }
 
-fn build_boringssl(config: &mut CmakeConfig) {
-    let boringssl_artifact = boringssl_src::Build::new().build();
-    config.define(
-        "OPENSSL_ROOT_DIR",
-        format!("{}", boringssl_artifact.root_dir().display()),
+#[cfg(feature = "secure")]
+fn copy_dir_all(src: &Path, dst: &Path) {
+    for entry in fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        let to = dst.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            fs::create_dir_all(&to).unwrap();
+            copy_dir_all(&entry.path(), &to);
+        } else {
+            fs::copy(entry.path(), &to).unwrap();
+        }
+    }
+}
+
+// `boringssl_src::Build` drives its own private `cmake::Config` with no
+// hook to pass extra defines, so it can't be told about the NDK toolchain.
+// Build BoringSSL ourselves against the same vendored sources
+// (`boringssl_src::source_dir()`), laid out the way `build_grpc` expects
+// (an `OPENSSL_ROOT_DIR` with `lib/` and `include/` subdirectories), so we
+// can hand the Android defines straight to its CMake invocation.
+#[cfg(feature = "secure")]
+fn build_boringssl_for_android(android: &AndroidConfig) -> PathBuf {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap()).join("boringssl-build");
+    let build_dir = out_dir.join("build");
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir).unwrap();
+    }
+    fs::create_dir_all(&build_dir).unwrap();
+
+    let mut cfg = CmakeConfig::new(boringssl_src::source_dir());
+    cfg.define("CMAKE_TOOLCHAIN_FILE", &android.toolchain_file)
+        .define("ANDROID_ABI", android.abi)
+        .define("ANDROID_PLATFORM", format!("android-{}", android.api_level))
+        .define("CMAKE_ANDROID_API", &android.api_level)
+        .out_dir(&out_dir);
+    cfg.build_target("ssl").build();
+    cfg.build_target("crypto").build();
+
+    let include_dir = out_dir.join("include");
+    if include_dir.exists() {
+        fs::remove_dir_all(&include_dir).unwrap();
+    }
+    fs::create_dir_all(&include_dir).unwrap();
+    copy_dir_all(
+        &boringssl_src::source_dir().join("src").join("include"),
+        &include_dir,
     );
+
+    let lib_dir = out_dir.join("lib");
+    if lib_dir.exists() {
+        fs::remove_dir_all(&lib_dir).unwrap();
+    }
+    fs::rename(&build_dir, &lib_dir).unwrap();
+
+    out_dir
+}
+
+// `boringssl-src` is an optional dependency gated behind `secure`, so the
+// call into `build_boringssl` below must be too.
+#[cfg(feature = "secure")]
+fn configure_ssl_provider(config: &mut CmakeConfig, android: Option<&AndroidConfig>) {
+    if cfg!(feature = "openssl") {
+        if cfg!(feature = "openssl-vendored") {
+            config.register_dep("openssl");
+        }
+    } else {
+        build_boringssl(config, android);
+    }
+}
+
+#[cfg(not(feature = "secure"))]
+fn configure_ssl_provider(_config: &mut CmakeConfig, _android: Option<&AndroidConfig>) {}
+
+#[cfg(feature = "secure")]
+fn build_boringssl(config: &mut CmakeConfig, android: Option<&AndroidConfig>) {
+    let (root_dir, lib_dir) = match android {
+        Some(android) => {
+            let root_dir = build_boringssl_for_android(android);
+            let lib_dir = root_dir.join("lib");
+            (root_dir, lib_dir)
+        }
+        None => {
+            let artifact = boringssl_src::Build::new().build();
+            (artifact.root_dir().to_path_buf(), artifact.lib_dir())
+        }
+    };
+    config.define("OPENSSL_ROOT_DIR", format!("{}", root_dir.display()));
     // To avoid linking system library, set lib path explicitly.
-    println!(
-        "cargo:rustc-link-search=native={}",
-        boringssl_artifact.lib_dir().display()
-    );
+    println!("cargo:rustc-link-search=native={}", lib_dir.display());
 }
 
 fn setup_libz(config: &mut CmakeConfig) {
@@ -437,6 +764,10 @@ fn main() {
         for inc_path in lib_core.include_paths {
             cc.include(inc_path);
         }
+    } else if !cfg!(feature = "static-grpc") && try_system_lib(&mut cc, library) {
+        // A compatible system gRPC was found; skip the vendored build.
+    } else if try_vcpkg(&mut cc, library) {
+        // vcpkg found a prebuilt package and already wired up cargo metadata.
     } else {
         build_grpc(&mut cc, library);
     }
@@ -0,0 +1,5 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+#![allow(dead_code, non_camel_case_types, non_snake_case, non_upper_case_globals)]
+
+include!(env!("BINDING_PATH"));